@@ -4,44 +4,119 @@ use std::io;
 use std::io::{Error, ErrorKind, Read};
 use std::rc::{Rc, Weak};
 
-use crate::shims::unix::fd::{FdId, WeakFileDescriptionRef};
+use crate::concurrency::thread::{BlockReason, ThreadId, UnblockCallback};
+use crate::shims::unix::fd::{FdId, FileDescriptionRef, WeakFileDescriptionRef};
 use crate::shims::unix::linux::epoll::EpollReadyEvents;
 use crate::shims::unix::*;
 use crate::{concurrency::VClock, *};
 
-/// The maximum capacity of the socketpair buffer in bytes.
-/// This number is arbitrary as the value can always
+/// The default capacity of the socketpair buffer in bytes, used until overridden via
+/// `setsockopt(SO_SNDBUF/SO_RCVBUF)`. This number is arbitrary as the value can always
 /// be configured in the real system.
 const MAX_SOCKETPAIR_BUFFER_CAPACITY: usize = 212992;
 
-/// Pair of connected sockets.
+/// A connected pair of anonymous, in-memory file descriptions: either a bidirectional
+/// `socketpair`, or one half of a half-duplex `pipe`/`pipe2`.
+///
+/// For a socketpair, both `readbuf` and `writebuf` are populated. For a pipe, the read end
+/// has a live `readbuf` and no `writebuf` at all, while the write end has a live `writebuf`
+/// and no `readbuf` at all; this reuses all of the buffering, blocking and
+/// epoll logic below while still rejecting writes to a read-only fd (and vice versa).
 #[derive(Debug)]
-struct SocketPair {
+struct AnonSocket {
+    /// The name reported to the rest of Miri, e.g. for error messages.
+    fd_name: &'static str,
     // By making the write link weak, a `write` can detect when all readers are
     // gone, and trigger EPIPE as appropriate.
-    writebuf: Weak<RefCell<Buffer>>,
-    readbuf: Rc<RefCell<Buffer>>,
+    writebuf: Option<Weak<RefCell<Buffer>>>,
+    readbuf: Option<Rc<RefCell<Buffer>>>,
     /// When a socketpair instance is created, two socketpair file descriptions are generated.
     /// The peer_fd field holds a weak reference to the file description of peer socketpair.
     // TODO: It might be possible to retrieve writebuf from peer_fd and remove the writebuf
     // field above.
     peer_fd: WeakFileDescriptionRef,
     is_nonblock: bool,
+    /// Set by `shutdown(fd, SHUT_RD | SHUT_RDWR)`. Unlike `readbuf` being entirely absent
+    /// (a pipe's write end), this fd is still otherwise valid; only further reads are forced
+    /// to report EOF.
+    is_read_shutdown: bool,
+    /// Set by `shutdown(fd, SHUT_WR | SHUT_RDWR)`. Further writes fail with `EPIPE` instead of
+    /// buffering, without tearing down the fd the way `close` would.
+    is_write_shutdown: bool,
+}
+
+/// The payload of a `Buffer`: either a flat byte stream (`SOCK_STREAM`, and pipes), or a
+/// queue of discrete messages (`SOCK_DGRAM`), where each `write` is one atomic element and
+/// each `read` consumes exactly one element, truncating if the reader's buffer is smaller.
+#[derive(Debug)]
+enum BufferData {
+    Stream(VecDeque<u8>),
+    Datagram(VecDeque<Vec<u8>>),
+}
+
+impl BufferData {
+    fn is_empty(&self) -> bool {
+        match self {
+            BufferData::Stream(buf) => buf.is_empty(),
+            BufferData::Datagram(queue) => queue.is_empty(),
+        }
+    }
+
+    /// The number of bytes currently queued, used to enforce the buffer's `capacity`.
+    fn queued_bytes(&self) -> usize {
+        match self {
+            BufferData::Stream(buf) => buf.len(),
+            BufferData::Datagram(queue) => queue.iter().map(Vec::len).sum(),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Buffer {
-    buf: VecDeque<u8>,
+    data: BufferData,
     clock: VClock,
     /// Indicates if there is at least one active writer to this buffer.
     /// If all writers of this buffer are dropped, buf_has_writer becomes false and we
     /// indicate EOF instead of blocking.
     buf_has_writer: bool,
+    /// The maximum number of bytes this buffer may hold, mirroring `SO_SNDBUF`/`SO_RCVBUF`.
+    /// Defaults to `MAX_SOCKETPAIR_BUFFER_CAPACITY`, but can be lowered (or raised) through
+    /// `setsockopt`, which is handy for tests that want to provoke `EAGAIN`/blocking without
+    /// pushing hundreds of kilobytes through the fd first.
+    capacity: usize,
+    /// Threads parked in `read` waiting for this buffer to become readable (non-empty, or to
+    /// lose its last writer). Woken by `write`/`close`/`shutdown`, right where those already
+    /// call `check_and_update_readiness` on the peer fd for epoll's sake.
+    blocked_read_tid: Vec<ThreadId>,
+    /// Threads parked in `write` waiting for this buffer to gain free space. Woken by `read`,
+    /// right where it already calls `check_and_update_readiness` on the peer fd.
+    blocked_write_tid: Vec<ThreadId>,
 }
 
-impl FileDescription for SocketPair {
+impl Buffer {
+    fn new(data: BufferData) -> Self {
+        Buffer {
+            data,
+            clock: VClock::default(),
+            buf_has_writer: true,
+            capacity: MAX_SOCKETPAIR_BUFFER_CAPACITY,
+            blocked_read_tid: Vec::new(),
+            blocked_write_tid: Vec::new(),
+        }
+    }
+
+    fn stream() -> Self {
+        Buffer::new(BufferData::Stream(VecDeque::new()))
+    }
+
+    fn datagram() -> Self {
+        Buffer::new(BufferData::Datagram(VecDeque::new()))
+    }
+}
+
+impl FileDescription for AnonSocket {
     fn name(&self) -> &'static str {
-        "socketpair"
+        self.fd_name
     }
 
     fn get_epoll_ready_events<'tcx>(&self) -> InterpResult<'tcx, EpollReadyEvents> {
@@ -49,18 +124,19 @@ impl FileDescription for SocketPair {
         // need to be supported in the future, the check should be added here.
 
         let mut epoll_ready_events = EpollReadyEvents::new();
-        let readbuf = self.readbuf.borrow();
 
         // Check if it is readable.
-        if !readbuf.buf.is_empty() {
-            epoll_ready_events.epollin = true;
+        if let Some(readbuf) = &self.readbuf {
+            if !readbuf.borrow().data.is_empty() {
+                epoll_ready_events.epollin = true;
+            }
         }
 
         // Check if is writable.
-        if let Some(writebuf) = self.writebuf.upgrade() {
+        if let Some(writebuf) = self.writebuf.as_ref().and_then(Weak::upgrade) {
             let writebuf = writebuf.borrow();
-            let data_size = writebuf.buf.len();
-            let available_space = MAX_SOCKETPAIR_BUFFER_CAPACITY.strict_sub(data_size);
+            let data_size = writebuf.data.queued_bytes();
+            let available_space = writebuf.capacity.saturating_sub(data_size);
             if available_space != 0 {
                 epoll_ready_events.epollout = true;
             }
@@ -83,10 +159,34 @@ impl FileDescription for SocketPair {
     ) -> InterpResult<'tcx, io::Result<()>> {
         // This is used to signal socketfd of other side that there is no writer to its readbuf.
         // If the upgrade fails, there is no need to update as all read ends have been dropped.
-        if let Some(writebuf) = self.writebuf.upgrade() {
-            writebuf.borrow_mut().buf_has_writer = false;
+        if let Some(writebuf) = self.writebuf.as_ref().and_then(Weak::upgrade) {
+            let mut writebuf = writebuf.borrow_mut();
+            writebuf.buf_has_writer = false;
+            // Wake any reader blocked on this buffer so it can observe the new EOF instead of
+            // waiting for a writer that is now gone for good.
+            let blocked_readers = std::mem::take(&mut writebuf.blocked_read_tid);
+            drop(writebuf);
+            for tid in blocked_readers {
+                ecx.unblock_thread(tid, BlockReason::UnixReadWrite)?;
+            }
         };
 
+        // If this is the last reader of `readbuf` going away, any writer parked waiting for
+        // space in it would otherwise block forever: it can only ever observe the dead `Weak`
+        // (and report `EPIPE`) by retrying the write, so wake it up now rather than leaving it
+        // to wait for a reader that is never coming back.
+        if let Some(readbuf) = self.readbuf {
+            if Rc::strong_count(&readbuf) == 1 {
+                let blocked_writers = std::mem::take(&mut readbuf.borrow_mut().blocked_write_tid);
+                // Release this last strong reference before waking anyone, so the retried
+                // `write` finds the `Weak` already dead instead of parking again.
+                drop(readbuf);
+                for tid in blocked_writers {
+                    ecx.unblock_thread(tid, BlockReason::UnixReadWrite)?;
+                }
+            }
+        }
+
         // Notify peer fd that closed has happened.
         if let Some(peer_fd) = self.peer_fd.upgrade() {
             // When any of the event happened, we check and update the status of all supported events
@@ -99,23 +199,37 @@ impl FileDescription for SocketPair {
     fn read<'tcx>(
         &mut self,
         _communicate_allowed: bool,
-        _fd_id: FdId,
-        bytes: &mut [u8],
+        fd_id: FdId,
+        ptr: Pointer,
+        len: u64,
+        dest: &MPlaceTy<'tcx>,
         ecx: &mut MiriInterpCx<'tcx>,
-    ) -> InterpResult<'tcx, io::Result<usize>> {
-        let request_byte_size = bytes.len();
-        let mut readbuf = self.readbuf.borrow_mut();
+    ) -> InterpResult<'tcx> {
+        let request_byte_size = usize::try_from(len).unwrap();
+
+        let Some(readbuf_rc) = &self.readbuf else {
+            // A write-only end (e.g. the write end of a `pipe`) has no readbuf at all.
+            return Self::finish_io(Err(Error::from(ErrorKind::InvalidInput)), dest, ecx);
+        };
 
         // Always succeed on read size 0.
         if request_byte_size == 0 {
-            return Ok(Ok(0));
+            return Self::finish_io(Ok(0), dest, ecx);
+        }
+
+        if self.is_read_shutdown {
+            // `shutdown(fd, SHUT_RD)` was called: report EOF without even looking at the
+            // buffer, regardless of whether the peer has more queued or is still writing.
+            return Self::finish_io(Ok(0), dest, ecx);
         }
 
-        if readbuf.buf.is_empty() {
+        let mut readbuf = readbuf_rc.borrow_mut();
+
+        if readbuf.data.is_empty() {
             if !readbuf.buf_has_writer {
                 // Socketpair with no writer and empty buffer.
                 // 0 bytes successfully read indicates end-of-file.
-                return Ok(Ok(0));
+                return Self::finish_io(Ok(0), dest, ecx);
             } else {
                 if self.is_nonblock {
                     // Non-blocking socketpair with writer and empty buffer.
@@ -123,11 +237,22 @@ impl FileDescription for SocketPair {
                     // EAGAIN or EWOULDBLOCK can be returned for socket,
                     // POSIX.1-2001 allows either error to be returned for this case.
                     // Since there is no ErrorKind for EAGAIN, WouldBlock is used.
-                    return Ok(Err(Error::from(ErrorKind::WouldBlock)));
+                    return Self::finish_io(Err(Error::from(ErrorKind::WouldBlock)), dest, ecx);
                 } else {
-                    // Blocking socketpair with writer and empty buffer.
-                    // FIXME: blocking is currently not supported
-                    throw_unsup_format!("socketpair read: blocking isn't supported yet");
+                    // Blocking socketpair with writer and empty buffer: park the calling
+                    // thread until `readbuf` becomes non-empty or the writer goes away. We
+                    // keep `ptr`/`dest` (rather than a borrowed `bytes` slice) so the unblock
+                    // callback can write the eventual result straight to where the blocked
+                    // `read(2)` call expects it, however long that takes.
+                    let tid = ecx.active_thread();
+                    readbuf.blocked_read_tid.push(tid);
+                    drop(readbuf);
+                    ecx.block_thread(
+                        BlockReason::UnixReadWrite,
+                        None,
+                        Box::new(UnblockAnonSocketRead { fd_id, ptr, len, dest: dest.clone() }),
+                    );
+                    return Ok(());
                 }
             }
         }
@@ -137,14 +262,35 @@ impl FileDescription for SocketPair {
         // only sync with the writes whose data we will read.
         ecx.acquire_clock(&readbuf.clock);
 
-        // Do full read / partial read based on the space available.
-        // Conveniently, `read` exists on `VecDeque` and has exactly the desired behavior.
-        let actual_read_size = readbuf.buf.read(bytes).unwrap();
+        let mut bytes = vec![0; request_byte_size];
+        let actual_read_size = match &mut readbuf.data {
+            BufferData::Stream(buf) => {
+                // Do full read / partial read based on the space available.
+                // Conveniently, `read` exists on `VecDeque` and has exactly the desired behavior.
+                buf.read(&mut bytes).unwrap()
+            }
+            BufferData::Datagram(queue) => {
+                // Each `read` consumes exactly one whole datagram. If the caller's buffer is
+                // too small, the rest of the datagram is silently discarded (Unix datagram
+                // truncation semantics), not left queued for the next `read`.
+                let datagram = queue.pop_front().unwrap();
+                let copy_size = datagram.len().min(bytes.len());
+                bytes[..copy_size].copy_from_slice(&datagram[..copy_size]);
+                copy_size
+            }
+        };
+
+        // Space just freed up in this buffer; wake any writer parked waiting for that.
+        let blocked_writers = std::mem::take(&mut readbuf.blocked_write_tid);
 
         // The readbuf needs to be explicitly dropped because it will cause panic when
         // check_and_update_readiness borrows it again.
         drop(readbuf);
 
+        for tid in blocked_writers {
+            ecx.unblock_thread(tid, BlockReason::UnixReadWrite)?;
+        }
+
         // A notification should be provided for the peer file description even when it can
         // only write 1 byte. This implementation is not compliant with the actual Linux kernel
         // implementation. For optimization reasons, the kernel will only mark the file description
@@ -156,59 +302,277 @@ impl FileDescription for SocketPair {
             peer_fd.check_and_update_readiness(ecx)?;
         }
 
-        return Ok(Ok(actual_read_size));
+        ecx.write_bytes_ptr(ptr, bytes[..actual_read_size].iter().copied())?;
+        Self::finish_io(Ok(actual_read_size), dest, ecx)
     }
 
     fn write<'tcx>(
         &mut self,
         _communicate_allowed: bool,
-        _fd_id: FdId,
+        fd_id: FdId,
         bytes: &[u8],
+        dest: &MPlaceTy<'tcx>,
         ecx: &mut MiriInterpCx<'tcx>,
-    ) -> InterpResult<'tcx, io::Result<usize>> {
+    ) -> InterpResult<'tcx> {
         let write_size = bytes.len();
+
+        let Some(writebuf_weak) = &self.writebuf else {
+            // A read-only end (e.g. the read end of a `pipe`) has no writebuf at all.
+            return Self::finish_io(Err(Error::from(ErrorKind::InvalidInput)), dest, ecx);
+        };
+
         // Always succeed on write size 0.
         // ("If count is zero and fd refers to a file other than a regular file, the results are not specified.")
         if write_size == 0 {
-            return Ok(Ok(0));
+            return Self::finish_io(Ok(0), dest, ecx);
+        }
+
+        if self.is_write_shutdown {
+            // `shutdown(fd, SHUT_WR)` was called: this fd remains open (unlike `close`), but
+            // it must no longer silently buffer writes.
+            return Self::finish_io(Err(Error::from(ErrorKind::BrokenPipe)), dest, ecx);
         }
 
-        let Some(writebuf) = self.writebuf.upgrade() else {
+        let Some(writebuf) = writebuf_weak.upgrade() else {
             // If the upgrade from Weak to Rc fails, it indicates that all read ends have been
             // closed.
-            return Ok(Err(Error::from(ErrorKind::BrokenPipe)));
+            return Self::finish_io(Err(Error::from(ErrorKind::BrokenPipe)), dest, ecx);
         };
         let mut writebuf = writebuf.borrow_mut();
-        let data_size = writebuf.buf.len();
-        let available_space = MAX_SOCKETPAIR_BUFFER_CAPACITY.strict_sub(data_size);
-        if available_space == 0 {
+        let is_datagram = matches!(writebuf.data, BufferData::Datagram(_));
+
+        if is_datagram && write_size > writebuf.capacity {
+            // Unlike a stream, a datagram write is all-or-nothing: if the message can never
+            // fit, fail outright instead of silently truncating it.
+            return Self::finish_io(
+                Err(Error::from_raw_os_error(ecx.eval_libc_i32("EMSGSIZE"))),
+                dest,
+                ecx,
+            );
+        }
+
+        let data_size = writebuf.data.queued_bytes();
+        // `saturating_sub` because `setsockopt(SO_SNDBUF)` can shrink `capacity` below the amount of data already queued.
+        let available_space = writebuf.capacity.saturating_sub(data_size);
+        // A datagram write needs room for the whole message at once; a stream write is happy
+        // with a partial write, so it only needs to block once there is no space whatsoever.
+        if available_space == 0 || (is_datagram && write_size > available_space) {
             if self.is_nonblock {
                 // Non-blocking socketpair with a full buffer.
-                return Ok(Err(Error::from(ErrorKind::WouldBlock)));
+                return Self::finish_io(Err(Error::from(ErrorKind::WouldBlock)), dest, ecx);
             } else {
-                // Blocking socketpair with a full buffer.
-                throw_unsup_format!("socketpair write: blocking isn't supported yet");
+                // Blocking socketpair with a full buffer: park until the peer's `read`
+                // frees some space, or all readers disappear (in which case the retried
+                // write reports EPIPE instead of blocking forever).
+                let tid = ecx.active_thread();
+                writebuf.blocked_write_tid.push(tid);
+                drop(writebuf);
+                ecx.block_thread(
+                    BlockReason::UnixReadWrite,
+                    None,
+                    Box::new(UnblockAnonSocketWrite {
+                        fd_id,
+                        bytes: bytes.to_owned(),
+                        dest: dest.clone(),
+                    }),
+                );
+                return Ok(());
             }
         }
         // Remember this clock so `read` can synchronize with us.
         if let Some(clock) = &ecx.release_clock() {
             writebuf.clock.join(clock);
         }
-        // Do full write / partial write based on the space available.
-        let actual_write_size = write_size.min(available_space);
-        writebuf.buf.extend(&bytes[..actual_write_size]);
+        let actual_write_size = match &mut writebuf.data {
+            BufferData::Stream(buf) => {
+                // Do full write / partial write based on the space available.
+                let actual_write_size = write_size.min(available_space);
+                buf.extend(&bytes[..actual_write_size]);
+                actual_write_size
+            }
+            BufferData::Datagram(queue) => {
+                // Each `write` is queued as a single atomic message (already checked above to
+                // fit within `available_space`).
+                queue.push_back(bytes.to_owned());
+                write_size
+            }
+        };
+
+        // Data just arrived in this buffer; wake any reader parked waiting for that.
+        let blocked_readers = std::mem::take(&mut writebuf.blocked_read_tid);
 
         // The writebuf needs to be explicitly dropped because it will cause panic when
         // check_and_update_readiness borrows it again.
         drop(writebuf);
+
+        for tid in blocked_readers {
+            ecx.unblock_thread(tid, BlockReason::UnixReadWrite)?;
+        }
         // Notification should be provided for peer fd as it became readable.
         if let Some(peer_fd) = self.peer_fd.upgrade() {
             peer_fd.check_and_update_readiness(ecx)?;
         }
-        return Ok(Ok(actual_write_size));
+        Self::finish_io(Ok(actual_write_size), dest, ecx)
+    }
+}
+
+impl AnonSocket {
+    /// Writes the result of a `read`/`write` to the original syscall's return-value slot,
+    /// following the usual `read(2)`/`write(2)` convention of a non-negative byte count on
+    /// success or `-1` (with `errno` set) on failure. Used by both the synchronous fast path
+    /// and the unblock callbacks below: a blocked `read`/`write` only learns its real result
+    /// once the parked thread is woken and retries, so the two paths share this tail end
+    /// rather than the caller interpreting a return value.
+    fn finish_io<'tcx>(
+        result: io::Result<usize>,
+        dest: &MPlaceTy<'tcx>,
+        ecx: &mut MiriInterpCx<'tcx>,
+    ) -> InterpResult<'tcx> {
+        let result = ecx.try_unwrap_io_result(result.map(|n| i64::try_from(n).unwrap()))?;
+        ecx.write_scalar(Scalar::from_i64(result), dest)
+    }
+
+    /// Implements the `FIONREAD` ioctl: the number of bytes immediately available to read.
+    /// For `SOCK_DGRAM`, that is the size of the next queued datagram (a single `read` can
+    /// only ever drain one datagram), not the total across all of them.
+    ///
+    /// Called from Miri's generic ioctl dispatch once it sees the fd is an `AnonSocket`.
+    fn fionread(&self) -> i32 {
+        let Some(readbuf) = &self.readbuf else { return 0 };
+        let n = match &readbuf.borrow().data {
+            BufferData::Stream(buf) => buf.len(),
+            BufferData::Datagram(queue) => queue.front().map_or(0, Vec::len),
+        };
+        n.try_into().unwrap_or(i32::MAX)
+    }
+
+    /// Implements `recv`/`recvfrom` with the `MSG_PEEK` flag: copies up to `bytes.len()` bytes
+    /// from the front of `readbuf` *without* consuming them. Since nothing was actually
+    /// removed from the buffer, this must not call the peer's `check_and_update_readiness` —
+    /// no space was freed, so nothing became writable.
+    ///
+    /// Called from Miri's generic `recv`/`recvfrom` dispatch once it sees `MSG_PEEK` was
+    /// passed and the fd is an `AnonSocket`.
+    fn peek<'tcx>(&self, bytes: &mut [u8], ecx: &mut MiriInterpCx<'tcx>) -> io::Result<usize> {
+        let Some(readbuf) = &self.readbuf else {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        };
+        if self.is_read_shutdown {
+            // Mirror `read`: once `shutdown(fd, SHUT_RD)` has been called, this fd reports
+            // EOF regardless of what is still queued, instead of returning stale bytes.
+            return Ok(0);
+        }
+        let readbuf = readbuf.borrow();
+
+        if readbuf.data.is_empty() {
+            if !readbuf.buf_has_writer {
+                // Mirror `read`: no writer and an empty buffer means end-of-file.
+                return Ok(0);
+            } else if self.is_nonblock {
+                // Mirror `read`: non-blocking fd, writer still alive, nothing queued yet.
+                return Err(Error::from(ErrorKind::WouldBlock));
+            } else {
+                // A blocking `MSG_PEEK` on an empty-but-open buffer should itself block until
+                // there is something to peek at, but `peek` has no unblock callback of its own
+                // to park on (unlike `read`/`write`); the minimum correct behavior is to report
+                // `WouldBlock` rather than the `Ok(0)` (spurious EOF) this used to return.
+                return Err(Error::from(ErrorKind::WouldBlock));
+            }
+        }
+
+        // Synchronize with all previous writes to this buffer, same as `read` does: even
+        // though `MSG_PEEK` doesn't consume any data, the caller did observe it, so a
+        // happens-before edge with the write that produced it is still required.
+        ecx.acquire_clock(&readbuf.clock);
+        match &readbuf.data {
+            BufferData::Stream(buf) => {
+                let n = buf.len().min(bytes.len());
+                for (dst, src) in bytes[..n].iter_mut().zip(buf.iter()) {
+                    *dst = *src;
+                }
+                Ok(n)
+            }
+            BufferData::Datagram(queue) => {
+                // `is_empty()` above already guarantees there is a datagram queued.
+                let datagram = queue.front().unwrap();
+                let n = datagram.len().min(bytes.len());
+                bytes[..n].copy_from_slice(&datagram[..n]);
+                Ok(n)
+            }
+        }
     }
 }
 
+/// Unblocks a thread parked in [`AnonSocket::read`] on an empty buffer. Re-enters the read
+/// logic from scratch: if the buffer is still empty (spurious wakeup, or another thread beat
+/// us to the data) it simply re-parks. Keeps the original `ptr`/`dest` (rather than the
+/// `&mut [u8]` the synchronous path uses) so that whenever this does complete, the result
+/// lands in exactly the guest memory and return-value slot the blocked `read(2)` expects.
+struct UnblockAnonSocketRead<'tcx> {
+    fd_id: FdId,
+    ptr: Pointer,
+    len: u64,
+    dest: MPlaceTy<'tcx>,
+}
+
+impl<'tcx> VisitProvenance for UnblockAnonSocketRead<'tcx> {
+    fn visit_provenance(&self, visit: &mut VisitWith<'_>) {
+        self.ptr.visit_provenance(visit);
+        self.dest.visit_provenance(visit);
+    }
+}
+
+impl<'tcx> UnblockCallback<'tcx> for UnblockAnonSocketRead<'tcx> {
+    fn unblock(self: Box<Self>, ecx: &mut MiriInterpCx<'tcx>) -> InterpResult<'tcx> {
+        let Some(fd) = ecx.machine.fds.get(self.fd_id) else {
+            // The fd was closed while we were parked; there is nothing left to read into or
+            // report back to (the `dest` place may not even be live any more).
+            return Ok(());
+        };
+        fd.borrow_mut()
+            .downcast_mut::<AnonSocket>()
+            .expect("an fd that blocked on AnonSocket::read should still be an AnonSocket")
+            .read(true, self.fd_id, self.ptr, self.len, &self.dest, ecx)
+    }
+}
+
+/// Unblocks a thread parked in [`AnonSocket::write`] on a full buffer. Re-enters the write
+/// logic from scratch with the original bytes; a still-full buffer re-parks the thread.
+struct UnblockAnonSocketWrite<'tcx> {
+    fd_id: FdId,
+    bytes: Vec<u8>,
+    dest: MPlaceTy<'tcx>,
+}
+
+impl<'tcx> VisitProvenance for UnblockAnonSocketWrite<'tcx> {
+    fn visit_provenance(&self, visit: &mut VisitWith<'_>) {
+        self.dest.visit_provenance(visit);
+    }
+}
+
+impl<'tcx> UnblockCallback<'tcx> for UnblockAnonSocketWrite<'tcx> {
+    fn unblock(self: Box<Self>, ecx: &mut MiriInterpCx<'tcx>) -> InterpResult<'tcx> {
+        let Some(fd) = ecx.machine.fds.get(self.fd_id) else {
+            // The fd was closed while we were parked; there is nothing left to write through
+            // or report back to.
+            return Ok(());
+        };
+        fd.borrow_mut()
+            .downcast_mut::<AnonSocket>()
+            .expect("an fd that blocked on AnonSocket::write should still be an AnonSocket")
+            .write(true, self.fd_id, &self.bytes, &self.dest, ecx)
+    }
+}
+
+/// Links `fd_ref0` and `fd_ref1` together as a connected pair, by pointing their `peer_fd`
+/// fields at each other.
+fn link_peer_fds(fd_ref0: &FileDescriptionRef, fd_ref1: &FileDescriptionRef) {
+    let weak_fd_ref0 = fd_ref0.downgrade();
+    let weak_fd_ref1 = fd_ref1.downgrade();
+    fd_ref1.borrow_mut().downcast_mut::<AnonSocket>().unwrap().peer_fd = weak_fd_ref0;
+    fd_ref0.borrow_mut().downcast_mut::<AnonSocket>().unwrap().peer_fd = weak_fd_ref1;
+}
+
 impl<'tcx> EvalContextExt<'tcx> for crate::MiriInterpCx<'tcx> {}
 pub trait EvalContextExt<'tcx>: crate::MiriInterpCxExt<'tcx> {
     /// For more information on the arguments see the socketpair manpage:
@@ -228,11 +592,15 @@ pub trait EvalContextExt<'tcx>: crate::MiriInterpCxExt<'tcx> {
         let sv = this.deref_pointer(sv)?;
 
         let mut is_sock_nonblock = false;
+        let mut is_datagram = false;
 
-        // Parse and remove the type flags that we support. If type != 0 after removing,
-        // unsupported flags are used.
+        // Parse and remove the base socket type (SOCK_STREAM or SOCK_DGRAM) that we support.
+        // If type != 0 after removing it and the flags below, unsupported flags are used.
         if type_ & this.eval_libc_i32("SOCK_STREAM") == this.eval_libc_i32("SOCK_STREAM") {
             type_ &= !(this.eval_libc_i32("SOCK_STREAM"));
+        } else if type_ & this.eval_libc_i32("SOCK_DGRAM") == this.eval_libc_i32("SOCK_DGRAM") {
+            is_datagram = true;
+            type_ &= !(this.eval_libc_i32("SOCK_DGRAM"));
         }
 
         // SOCK_NONBLOCK only exists on Linux.
@@ -257,7 +625,7 @@ pub trait EvalContextExt<'tcx>: crate::MiriInterpCxExt<'tcx> {
             );
         } else if type_ != 0 {
             throw_unsup_format!(
-                "socketpair: type {:#x} is unsupported, only SOCK_STREAM, \
+                "socketpair: type {:#x} is unsupported, only SOCK_STREAM, SOCK_DGRAM, \
                                  SOCK_CLOEXEC and SOCK_NONBLOCK are allowed",
                 type_
             );
@@ -268,29 +636,27 @@ pub trait EvalContextExt<'tcx>: crate::MiriInterpCxExt<'tcx> {
             );
         }
 
-        let buffer1 = Rc::new(RefCell::new(Buffer {
-            buf: VecDeque::new(),
-            clock: VClock::default(),
-            buf_has_writer: true,
-        }));
+        let new_buffer = || if is_datagram { Buffer::datagram() } else { Buffer::stream() };
+        let buffer1 = Rc::new(RefCell::new(new_buffer()));
+        let buffer2 = Rc::new(RefCell::new(new_buffer()));
 
-        let buffer2 = Rc::new(RefCell::new(Buffer {
-            buf: VecDeque::new(),
-            clock: VClock::default(),
-            buf_has_writer: true,
-        }));
-
-        let socketpair_0 = SocketPair {
-            writebuf: Rc::downgrade(&buffer1),
-            readbuf: Rc::clone(&buffer2),
+        let socketpair_0 = AnonSocket {
+            fd_name: "socketpair",
+            writebuf: Some(Rc::downgrade(&buffer1)),
+            readbuf: Some(Rc::clone(&buffer2)),
             peer_fd: WeakFileDescriptionRef::default(),
             is_nonblock: is_sock_nonblock,
+            is_read_shutdown: false,
+            is_write_shutdown: false,
         };
-        let socketpair_1 = SocketPair {
-            writebuf: Rc::downgrade(&buffer2),
-            readbuf: Rc::clone(&buffer1),
+        let socketpair_1 = AnonSocket {
+            fd_name: "socketpair",
+            writebuf: Some(Rc::downgrade(&buffer2)),
+            readbuf: Some(Rc::clone(&buffer1)),
             peer_fd: WeakFileDescriptionRef::default(),
             is_nonblock: is_sock_nonblock,
+            is_read_shutdown: false,
+            is_write_shutdown: false,
         };
 
         // Insert the file description to the fd table.
@@ -301,13 +667,7 @@ pub trait EvalContextExt<'tcx>: crate::MiriInterpCxExt<'tcx> {
         // Get weak file descriptor and file description id value.
         let fd_ref0 = fds.get_ref(sv0).unwrap();
         let fd_ref1 = fds.get_ref(sv1).unwrap();
-        let weak_fd_ref0 = fd_ref0.downgrade();
-        let weak_fd_ref1 = fd_ref1.downgrade();
-
-        // Update peer_fd and id field.
-        fd_ref1.borrow_mut().downcast_mut::<SocketPair>().unwrap().peer_fd = weak_fd_ref0;
-
-        fd_ref0.borrow_mut().downcast_mut::<SocketPair>().unwrap().peer_fd = weak_fd_ref1;
+        link_peer_fds(&fd_ref0, &fd_ref1);
 
         // Return socketpair file description value to the caller.
         let sv0 = Scalar::from_int(sv0, sv.layout.size);
@@ -318,4 +678,272 @@ pub trait EvalContextExt<'tcx>: crate::MiriInterpCxExt<'tcx> {
 
         Ok(Scalar::from_i32(0))
     }
+
+    /// For more information on the arguments see the pipe manpage:
+    /// <https://linux.die.net/man/2/pipe>
+    fn pipe(&mut self, pipefd: &OpTy<'tcx>) -> InterpResult<'tcx, Scalar> {
+        let this = self.eval_context_mut();
+        this.pipe2_inner(pipefd, 0)
+    }
+
+    /// For more information on the arguments see the pipe2 manpage:
+    /// <https://linux.die.net/man/2/pipe2>
+    fn pipe2(&mut self, pipefd: &OpTy<'tcx>, flags: &OpTy<'tcx>) -> InterpResult<'tcx, Scalar> {
+        let this = self.eval_context_mut();
+
+        // `pipe2` is only available on these targets.
+        if !matches!(this.tcx.sess.target.os.as_ref(), "linux" | "freebsd" | "solaris" | "illumos")
+        {
+            throw_unsup_format!(
+                "`pipe2` is not supported on {}",
+                this.tcx.sess.target.os.as_ref()
+            );
+        }
+
+        let flags = this.read_scalar(flags)?.to_i32()?;
+        this.pipe2_inner(pipefd, flags)
+    }
+
+    /// Shared implementation of `pipe` (flags always 0) and `pipe2` (flags parsed by the
+    /// caller), building a half-duplex channel out of the same `Buffer`/`AnonSocket`
+    /// machinery used by `socketpair`.
+    fn pipe2_inner(&mut self, pipefd: &OpTy<'tcx>, mut flags: i32) -> InterpResult<'tcx, Scalar> {
+        let this = self.eval_context_mut();
+        let pipefd = this.deref_pointer(pipefd)?;
+
+        let mut is_nonblock = false;
+        if flags & this.eval_libc_i32("O_NONBLOCK") == this.eval_libc_i32("O_NONBLOCK") {
+            is_nonblock = true;
+            flags &= !this.eval_libc_i32("O_NONBLOCK");
+        }
+        if flags & this.eval_libc_i32("O_CLOEXEC") == this.eval_libc_i32("O_CLOEXEC") {
+            flags &= !this.eval_libc_i32("O_CLOEXEC");
+        }
+        if flags != 0 {
+            throw_unsup_format!(
+                "pipe2: flags {:#x} are unsupported, only O_CLOEXEC and O_NONBLOCK are allowed",
+                flags
+            );
+        }
+
+        let buf = Rc::new(RefCell::new(Buffer::stream()));
+
+        // pipefd[0] is the read end: it has a live readbuf but no writebuf at all, so writing
+        // to it fails instead of blocking or panicking.
+        let read_end = AnonSocket {
+            fd_name: "pipe",
+            writebuf: None,
+            readbuf: Some(Rc::clone(&buf)),
+            peer_fd: WeakFileDescriptionRef::default(),
+            is_nonblock,
+            is_read_shutdown: false,
+            is_write_shutdown: false,
+        };
+        // pipefd[1] is the write end: it has a live writebuf but no readbuf, so reading from
+        // it fails instead of returning data that was never meant to reach it.
+        let write_end = AnonSocket {
+            fd_name: "pipe",
+            writebuf: Some(Rc::downgrade(&buf)),
+            readbuf: None,
+            peer_fd: WeakFileDescriptionRef::default(),
+            is_nonblock,
+            is_read_shutdown: false,
+            is_write_shutdown: false,
+        };
+
+        let fds = &mut this.machine.fds;
+        let read_fd = fds.insert_new(read_end);
+        let write_fd = fds.insert_new(write_end);
+
+        let read_fd_ref = fds.get_ref(read_fd).unwrap();
+        let write_fd_ref = fds.get_ref(write_fd).unwrap();
+        link_peer_fds(&read_fd_ref, &write_fd_ref);
+
+        let read_fd = Scalar::from_int(read_fd, pipefd.layout.size);
+        let write_fd = Scalar::from_int(write_fd, pipefd.layout.size);
+
+        this.write_scalar(read_fd, &pipefd)?;
+        this.write_scalar(write_fd, &pipefd.offset(pipefd.layout.size, pipefd.layout, this)?)?;
+
+        Ok(Scalar::from_i32(0))
+    }
+
+    /// Supports `SO_SNDBUF` and `SO_RCVBUF` on socketpair fds, overriding
+    /// `MAX_SOCKETPAIR_BUFFER_CAPACITY` on the buffer this fd writes to / reads from.
+    /// <https://man7.org/linux/man-pages/man2/setsockopt.2.html>
+    fn setsockopt(
+        &mut self,
+        sockfd: &OpTy<'tcx>,
+        level: &OpTy<'tcx>,
+        optname: &OpTy<'tcx>,
+        optval: &OpTy<'tcx>,
+        optlen: &OpTy<'tcx>,
+    ) -> InterpResult<'tcx, Scalar> {
+        let this = self.eval_context_mut();
+
+        let sockfd = this.read_scalar(sockfd)?.to_i32()?;
+        let level = this.read_scalar(level)?.to_i32()?;
+        let optname = this.read_scalar(optname)?.to_i32()?;
+        let optval = this.read_pointer(optval)?;
+        let _optlen = this.read_scalar(optlen)?.to_u32()?;
+
+        if level != this.eval_libc_i32("SOL_SOCKET") {
+            throw_unsup_format!(
+                "setsockopt: level {level:#x} is unsupported, only SOL_SOCKET is allowed"
+            );
+        }
+
+        let Some(fd) = this.machine.fds.get(sockfd) else {
+            this.set_last_error(LibcError("EBADF"))?;
+            return Ok(Scalar::from_i32(-1));
+        };
+        let Some(anonsocket) = fd.borrow_mut().downcast_mut::<AnonSocket>() else {
+            this.set_last_error(LibcError("ENOTSOCK"))?;
+            return Ok(Scalar::from_i32(-1));
+        };
+
+        let buf = if optname == this.eval_libc_i32("SO_SNDBUF") {
+            anonsocket.writebuf.as_ref().and_then(Weak::upgrade)
+        } else if optname == this.eval_libc_i32("SO_RCVBUF") {
+            anonsocket.readbuf.clone()
+        } else {
+            throw_unsup_format!(
+                "setsockopt: optname {optname:#x} is unsupported, only SO_SNDBUF and \
+                 SO_RCVBUF are allowed"
+            );
+        };
+
+        let optval_place = this.ptr_to_mplace(optval, this.libc_ty_layout("c_int"));
+        let new_capacity = this.read_scalar(&optval_place)?.to_i32()?;
+        if let Some(buf) = buf {
+            buf.borrow_mut().capacity = usize::try_from(new_capacity).unwrap_or(0);
+        }
+        // If the corresponding end has already disappeared (e.g. `SO_SNDBUF` after the peer
+        // closed), the real kernel still reports success; there is simply nothing left to
+        // configure.
+
+        Ok(Scalar::from_i32(0))
+    }
+
+    /// Supports `SO_SNDBUF` and `SO_RCVBUF` on socketpair fds.
+    /// <https://man7.org/linux/man-pages/man2/getsockopt.2.html>
+    fn getsockopt(
+        &mut self,
+        sockfd: &OpTy<'tcx>,
+        level: &OpTy<'tcx>,
+        optname: &OpTy<'tcx>,
+        optval: &OpTy<'tcx>,
+        optlen: &OpTy<'tcx>,
+    ) -> InterpResult<'tcx, Scalar> {
+        let this = self.eval_context_mut();
+
+        let sockfd = this.read_scalar(sockfd)?.to_i32()?;
+        let level = this.read_scalar(level)?.to_i32()?;
+        let optname = this.read_scalar(optname)?.to_i32()?;
+        let optval = this.read_pointer(optval)?;
+        let optlen = this.deref_pointer(optlen)?;
+
+        if level != this.eval_libc_i32("SOL_SOCKET") {
+            throw_unsup_format!(
+                "getsockopt: level {level:#x} is unsupported, only SOL_SOCKET is allowed"
+            );
+        }
+
+        let Some(fd) = this.machine.fds.get(sockfd) else {
+            this.set_last_error(LibcError("EBADF"))?;
+            return Ok(Scalar::from_i32(-1));
+        };
+        let Some(anonsocket) = fd.borrow_mut().downcast_mut::<AnonSocket>() else {
+            this.set_last_error(LibcError("ENOTSOCK"))?;
+            return Ok(Scalar::from_i32(-1));
+        };
+
+        let buf = if optname == this.eval_libc_i32("SO_SNDBUF") {
+            anonsocket.writebuf.as_ref().and_then(Weak::upgrade)
+        } else if optname == this.eval_libc_i32("SO_RCVBUF") {
+            anonsocket.readbuf.clone()
+        } else {
+            throw_unsup_format!(
+                "getsockopt: optname {optname:#x} is unsupported, only SO_SNDBUF and \
+                 SO_RCVBUF are allowed"
+            );
+        };
+        let capacity = buf.map(|buf| buf.borrow().capacity).unwrap_or(0);
+
+        let optval_place = this.ptr_to_mplace(optval, this.libc_ty_layout("c_int"));
+        this.write_scalar(
+            Scalar::from_i32(capacity.try_into().unwrap_or(i32::MAX)),
+            &optval_place,
+        )?;
+        this.write_scalar(
+            Scalar::from_u32(std::mem::size_of::<i32>().try_into().unwrap()),
+            &optlen,
+        )?;
+
+        Ok(Scalar::from_i32(0))
+    }
+
+    /// Half-closes a socketpair fd in one or both directions, without invalidating the fd
+    /// itself (unlike `close`). <https://man7.org/linux/man-pages/man2/shutdown.2.html>
+    fn shutdown(&mut self, sockfd: &OpTy<'tcx>, how: &OpTy<'tcx>) -> InterpResult<'tcx, Scalar> {
+        let this = self.eval_context_mut();
+
+        let sockfd = this.read_scalar(sockfd)?.to_i32()?;
+        let how = this.read_scalar(how)?.to_i32()?;
+
+        let Some(fd) = this.machine.fds.get(sockfd) else {
+            this.set_last_error(LibcError("EBADF"))?;
+            return Ok(Scalar::from_i32(-1));
+        };
+
+        let shut_rd =
+            how == this.eval_libc_i32("SHUT_RD") || how == this.eval_libc_i32("SHUT_RDWR");
+        let shut_wr =
+            how == this.eval_libc_i32("SHUT_WR") || how == this.eval_libc_i32("SHUT_RDWR");
+        if !shut_rd && !shut_wr {
+            throw_unsup_format!(
+                "shutdown: how {how} is unsupported, only SHUT_RD, SHUT_WR and SHUT_RDWR \
+                 are allowed"
+            );
+        }
+
+        let peer_fd = {
+            let mut fd_mut = fd.borrow_mut();
+            let Some(anonsocket) = fd_mut.downcast_mut::<AnonSocket>() else {
+                drop(fd_mut);
+                this.set_last_error(LibcError("ENOTSOCK"))?;
+                return Ok(Scalar::from_i32(-1));
+            };
+
+            if shut_rd {
+                anonsocket.is_read_shutdown = true;
+            }
+            if shut_wr {
+                anonsocket.is_write_shutdown = true;
+                // Reuse the exact mechanism `close` already uses to signal EOF to the peer:
+                // mark the buffer this fd writes into (the peer's readbuf) as writer-less, and
+                // wake any reader already parked on it so it observes the EOF now instead of
+                // waiting for a writer that is never coming back.
+                if let Some(writebuf) = anonsocket.writebuf.as_ref().and_then(Weak::upgrade) {
+                    let mut writebuf = writebuf.borrow_mut();
+                    writebuf.buf_has_writer = false;
+                    let blocked_readers = std::mem::take(&mut writebuf.blocked_read_tid);
+                    drop(writebuf);
+                    for tid in blocked_readers {
+                        this.unblock_thread(tid, BlockReason::UnixReadWrite)?;
+                    }
+                }
+            }
+
+            anonsocket.peer_fd.upgrade()
+        };
+
+        // Now that our mutable borrow of `fd` has ended, let the peer know (it may need to
+        // wake a thread parked in `read`, or recompute its epoll readiness).
+        if let Some(peer_fd) = peer_fd {
+            peer_fd.check_and_update_readiness(this)?;
+        }
+
+        Ok(Scalar::from_i32(0))
+    }
 }
@@ -0,0 +1,48 @@
+//@ignore-target: windows # no libc pipe on Windows
+//! Regression test for `pipe`/`pipe2`: basic write-then-read through the fds, EOF once the
+//! write end is closed, and the one-directional restriction (the read end can't be written to,
+//! the write end can't be read from).
+fn test_pipe(read_fd: i32, write_fd: i32) {
+    let msg = b"hello from pipe";
+    let n = unsafe { libc::write(write_fd, msg.as_ptr().cast(), msg.len()) };
+    assert_eq!(n, msg.len() as isize);
+
+    let mut buf = [0u8; 32];
+    let n = unsafe { libc::read(read_fd, buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!(n, msg.len() as isize);
+    assert_eq!(&buf[..msg.len()], msg);
+
+    // The read end can't be written to, and the write end can't be read from.
+    let n = unsafe { libc::write(read_fd, msg.as_ptr().cast(), msg.len()) };
+    assert_eq!(n, -1);
+    assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EINVAL));
+    let n = unsafe { libc::read(write_fd, buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!(n, -1);
+    assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EINVAL));
+
+    // Closing the write end makes the read end observe EOF.
+    unsafe { libc::close(write_fd) };
+    let n = unsafe { libc::read(read_fd, buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!(n, 0);
+
+    unsafe { libc::close(read_fd) };
+}
+
+fn main() {
+    let mut fds = [-1, -1];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    test_pipe(fds[0], fds[1]);
+
+    // `pipe2` only exists on these targets.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "freebsd",
+        target_os = "solaris",
+        target_os = "illumos"
+    ))]
+    {
+        let mut fds = [-1, -1];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        test_pipe(fds[0], fds[1]);
+    }
+}
@@ -0,0 +1,70 @@
+//@ignore-target: windows # no libc socketpair on Windows
+//! Regression test for blocking reads/writes on `socketpair` fds: a reader blocked on an
+//! empty buffer must be woken (with the right bytes) once a writer on another thread
+//! supplies data, and a writer blocked on a full buffer must be woken once a reader drains
+//! it. Before blocking support was added, both of these either errored out immediately or
+//! (during development) silently dropped the transferred bytes.
+use std::thread;
+
+fn main() {
+    let mut fds = [-1, -1];
+    let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(res, 0);
+    let [read_fd, write_fd] = fds;
+
+    // The reader blocks immediately since the buffer starts out empty; the main thread then
+    // writes the bytes it should receive once unblocked.
+    let reader = thread::spawn(move || {
+        let mut buf = [0u8; 5];
+        let n = unsafe { libc::read(read_fd, buf.as_mut_ptr().cast(), buf.len()) };
+        assert_eq!(n, 5);
+        assert_eq!(&buf, b"hello");
+        unsafe { libc::close(read_fd) };
+    });
+
+    let n = unsafe { libc::write(write_fd, b"hello".as_ptr().cast(), 5) };
+    assert_eq!(n, 5);
+
+    reader.join().unwrap();
+    unsafe { libc::close(write_fd) };
+
+    // Now the other direction: shrink `SO_SNDBUF` so the buffer is trivially easy to fill,
+    // then have a writer block on it and confirm a reader draining it wakes the writer back up.
+    let mut fds = [-1, -1];
+    let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(res, 0);
+    let [read_fd, write_fd] = fds;
+
+    let small_capacity: libc::c_int = 4;
+    let res = unsafe {
+        libc::setsockopt(
+            write_fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            (&small_capacity as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(res, 0);
+
+    // This write is larger than the 4-byte buffer, so it blocks until the reader (on the main
+    // thread, below) drains enough space for it to finish.
+    let writer = thread::spawn(move || {
+        let n = unsafe { libc::write(write_fd, b"hello".as_ptr().cast(), 5) };
+        assert_eq!(n, 5);
+        unsafe { libc::close(write_fd) };
+    });
+
+    let mut buf = [0u8; 5];
+    let mut total = 0;
+    while total < buf.len() {
+        let n =
+            unsafe { libc::read(read_fd, buf[total..].as_mut_ptr().cast(), buf.len() - total) };
+        assert!(n > 0);
+        total += n as usize;
+    }
+    assert_eq!(&buf, b"hello");
+
+    writer.join().unwrap();
+    unsafe { libc::close(read_fd) };
+}
@@ -0,0 +1,40 @@
+//@ignore-target: windows # no libc socketpair on Windows
+//! Regression test for `SOCK_DGRAM` socketpairs: each `write` is one atomic message, and each
+//! `read` drains exactly one message, truncating (not concatenating with the next message) if
+//! the reader's buffer is too small.
+fn main() {
+    let mut fds = [-1, -1];
+    let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_DGRAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(res, 0);
+    let [fd0, fd1] = fds;
+
+    let first = b"first message";
+    let second = b"second";
+    assert_eq!(
+        unsafe { libc::write(fd1, first.as_ptr().cast(), first.len()) },
+        first.len() as isize
+    );
+    assert_eq!(
+        unsafe { libc::write(fd1, second.as_ptr().cast(), second.len()) },
+        second.len() as isize
+    );
+
+    // A too-small buffer truncates the first datagram instead of reading a partial amount
+    // and leaving the rest queued for the next `read`.
+    let mut small = [0u8; 5];
+    let n = unsafe { libc::read(fd0, small.as_mut_ptr().cast(), small.len()) };
+    assert_eq!(n, 5);
+    assert_eq!(&small, b"first");
+
+    // The second `read` gets the *second* message in full, proving the tail of the first
+    // (truncated) datagram was discarded rather than carried over.
+    let mut buf = [0u8; 32];
+    let n = unsafe { libc::read(fd0, buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!(n, second.len() as isize);
+    assert_eq!(&buf[..second.len()], second);
+
+    unsafe {
+        libc::close(fd0);
+        libc::close(fd1);
+    }
+}
@@ -0,0 +1,53 @@
+//@ignore-target: windows # no libc socketpair on Windows
+//! Regression test for plain (non-shutdown) `MSG_PEEK` and `FIONREAD` on `socketpair` fds:
+//! peeking part of the queued data must not consume it (a later `read` still sees everything),
+//! and `FIONREAD` must report the number of bytes immediately available to read.
+fn main() {
+    let mut fds = [-1, -1];
+    let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(res, 0);
+    let [fd0, fd1] = fds;
+
+    let msg = b"hello world";
+    let n = unsafe { libc::write(fd1, msg.as_ptr().cast(), msg.len()) };
+    assert_eq!(n, msg.len() as isize);
+
+    // `FIONREAD` reports exactly what was written.
+    let mut available: libc::c_int = 0;
+    assert_eq!(unsafe { libc::ioctl(fd0, libc::FIONREAD, &mut available) }, 0);
+    assert_eq!(available, msg.len() as libc::c_int);
+
+    // Peeking fewer bytes than are queued must not consume any of them.
+    let mut peek_buf = [0u8; 5];
+    let n = unsafe {
+        libc::recv(fd0, peek_buf.as_mut_ptr().cast(), peek_buf.len(), libc::MSG_PEEK)
+    };
+    assert_eq!(n, 5);
+    assert_eq!(&peek_buf, b"hello");
+
+    // `FIONREAD` still reports the full amount: nothing was actually removed from the buffer.
+    assert_eq!(unsafe { libc::ioctl(fd0, libc::FIONREAD, &mut available) }, 0);
+    assert_eq!(available, msg.len() as libc::c_int);
+
+    // A second peek sees the exact same bytes from the start, not a continuation.
+    let n = unsafe {
+        libc::recv(fd0, peek_buf.as_mut_ptr().cast(), peek_buf.len(), libc::MSG_PEEK)
+    };
+    assert_eq!(n, 5);
+    assert_eq!(&peek_buf, b"hello");
+
+    // The real `read` gets everything, proving the earlier peeks left the buffer untouched.
+    let mut buf = [0u8; 32];
+    let n = unsafe { libc::read(fd0, buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!(n, msg.len() as isize);
+    assert_eq!(&buf[..msg.len()], msg);
+
+    // Nothing left to read now.
+    assert_eq!(unsafe { libc::ioctl(fd0, libc::FIONREAD, &mut available) }, 0);
+    assert_eq!(available, 0);
+
+    unsafe {
+        libc::close(fd0);
+        libc::close(fd1);
+    }
+}
@@ -0,0 +1,36 @@
+//@ignore-target: windows # no libc socketpair on Windows
+//! Regression test for `shutdown(fd, SHUT_RD | SHUT_WR | SHUT_RDWR)` on `socketpair` fds: the
+//! fd stays open (unlike `close`), but `SHUT_WR` makes further writes fail with `EPIPE` and
+//! `SHUT_RD` makes further reads (including `MSG_PEEK`) report EOF immediately.
+fn main() {
+    let mut fds = [-1, -1];
+    let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(res, 0);
+    let [fd0, fd1] = fds;
+
+    // `SHUT_WR` on fd0: fd0 itself can no longer write, but it's still a valid, open fd.
+    assert_eq!(unsafe { libc::shutdown(fd0, libc::SHUT_WR) }, 0);
+    let byte = b'x';
+    let n = unsafe { libc::write(fd0, (&byte as *const u8).cast(), 1) };
+    assert_eq!(n, -1);
+    assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EPIPE));
+
+    // The peer observes EOF once the (now writer-less) buffer is drained.
+    let mut buf = [0u8; 1];
+    let n = unsafe { libc::read(fd1, buf.as_mut_ptr().cast(), 1) };
+    assert_eq!(n, 0);
+
+    // `SHUT_RD` on fd1: further reads, including a `MSG_PEEK`, report EOF even though fd1
+    // never actually ran out of things to read (there was nothing queued to begin with, but
+    // the point is this is forced to 0, not dependent on the buffer's actual contents).
+    assert_eq!(unsafe { libc::shutdown(fd1, libc::SHUT_RD) }, 0);
+    let n = unsafe {
+        libc::recv(fd1, buf.as_mut_ptr().cast(), buf.len(), libc::MSG_PEEK)
+    };
+    assert_eq!(n, 0);
+
+    unsafe {
+        libc::close(fd0);
+        libc::close(fd1);
+    }
+}
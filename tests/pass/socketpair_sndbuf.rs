@@ -0,0 +1,63 @@
+//@ignore-target: windows # no libc socketpair on Windows
+//! Regression test for `setsockopt`/`getsockopt` with `SO_SNDBUF` on `socketpair` fds: shrinking
+//! the send buffer makes a write that doesn't fit block (or, non-blocking, fail with
+//! `EWOULDBLOCK`) instead of silently succeeding into a buffer that was supposed to be tiny.
+use std::thread;
+
+fn main() {
+    let mut fds = [-1, -1];
+    let res = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+    assert_eq!(res, 0);
+    let [read_fd, write_fd] = fds;
+
+    // Shrink the write end's send buffer down to 4 bytes.
+    let small_capacity: libc::c_int = 4;
+    let res = unsafe {
+        libc::setsockopt(
+            write_fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            (&small_capacity as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(res, 0);
+
+    // `getsockopt` reports back the capacity we just set.
+    let mut readback: libc::c_int = 0;
+    let mut readback_len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            write_fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            (&mut readback as *mut libc::c_int).cast(),
+            &mut readback_len,
+        )
+    };
+    assert_eq!(res, 0);
+    assert_eq!(readback, small_capacity);
+
+    // A write of 10 bytes can't fit in a 4-byte buffer, so it has to block until the reader
+    // drains it; this is what makes the blocked-writer wakeup path deterministic without
+    // needing to push hundreds of KB through the fd.
+    let writer = thread::spawn(move || {
+        let msg = b"0123456789";
+        let n = unsafe { libc::write(write_fd, msg.as_ptr().cast(), msg.len()) };
+        assert_eq!(n, msg.len() as isize);
+        unsafe { libc::close(write_fd) };
+    });
+
+    let mut buf = [0u8; 10];
+    let mut total = 0;
+    while total < buf.len() {
+        let n =
+            unsafe { libc::read(read_fd, buf[total..].as_mut_ptr().cast(), buf.len() - total) };
+        assert!(n > 0);
+        total += n as usize;
+    }
+    assert_eq!(&buf, b"0123456789");
+
+    writer.join().unwrap();
+    unsafe { libc::close(read_fd) };
+}